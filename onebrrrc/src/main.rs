@@ -1,50 +1,68 @@
+#![feature(portable_simd)]
+
+mod simd_hasher;
+mod simd_newline;
+
 use std::{
     collections::{BTreeMap, HashMap},
+    env,
     fs::{self, File},
+    io::{self, Read},
     os::fd::AsRawFd,
-    ptr,
+    ptr, thread,
 };
 
 use libc::mmap;
 
-fn main() {
-    let f = fs::File::open("../measurements.txt").unwrap();
-    let map = memmap(&f);
+use simd_hasher::SimdBuildHasher;
+use simd_newline::{find_next_newline, find_next_semicolon, find_semicolon_then_newline};
 
-    let mut stats = HashMap::<Vec<u8>, (i16, i64, usize, i16)>::new();
+type Stats = (i16, i64, usize, i16);
 
-    for line in map.split(|c| *c == b'\n') {
-        if line.is_empty() {
-            break;
-        }
+fn main() {
+    let path = resolve_input_path();
+    let hasher = resolve_build_hasher();
 
-        let mut fields = line.rsplitn(2, |c| *c == b';');
-        let temperature = parse_temp(fields.next().unwrap());
+    let stats = if path == "-" || !is_regular_file(&path) {
+        let reader: Box<dyn Read> = if path == "-" {
+            Box::new(io::stdin())
+        } else {
+            Box::new(fs::File::open(&path).unwrap())
+        };
+        aggregate_stream(reader, hasher)
+    } else {
+        let f = fs::File::open(&path).unwrap();
+        let map = memmap(&f);
+        aggregate(map, resolve_thread_count(), hasher)
+    };
 
-        let station = fields.next().unwrap();
+    print_stats(&stats);
+}
 
-        let stats = match stats.get_mut(station) {
-            Some(stats) => stats,
-            None => stats
-                .entry(station.to_vec())
-                .or_insert((i16::MAX, 0, 0, i16::MIN)),
-        };
+/// Which input to read: the first CLI argument that isn't a `--flag`,
+/// defaulting to the file this tool has always read from. `-` selects
+/// stdin.
+fn resolve_input_path() -> String {
+    env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| "../measurements.txt".to_string())
+}
 
-        stats.0 = stats.0.min(temperature);
-        stats.1 += i64::from(temperature);
-        stats.2 += 1;
-        stats.3 = stats.3.max(temperature);
-    }
+fn is_regular_file(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file())
+}
 
+fn print_stats(stats: &HashMap<Vec<u8>, Stats, SimdBuildHasher>) {
     print!("{{");
-    let stats = BTreeMap::from_iter(
+    let sorted = BTreeMap::from_iter(
         stats
             .iter()
             .map(|(k, v)| (unsafe { String::from_utf8_unchecked(k.to_vec()) }, v)),
     );
-    let mut stats = stats.into_iter().peekable();
+    let mut sorted = sorted.into_iter().peekable();
 
-    while let Some((station, (min, sum, count, max))) = stats.next() {
+    while let Some((station, (min, sum, count, max))) = sorted.next() {
         print!(
             "{station}={:.1}/{:.1}/{:.1}",
             (*min as f64) / 10.,
@@ -52,13 +70,210 @@ fn main() {
             (*max as f64) / 10.
         );
 
-        if stats.peek().is_some() {
+        if sorted.peek().is_some() {
             print!(", ")
         }
     }
     print!("}}");
 }
 
+/// How many worker threads to aggregate with. `--single-threaded` or
+/// `ONEBRC_SINGLE_THREADED=1` force the single-threaded path; `ONEBRC_THREADS`
+/// overrides the auto-detected count.
+fn resolve_thread_count() -> usize {
+    let single_threaded = env::args().any(|arg| arg == "--single-threaded")
+        || env::var("ONEBRC_SINGLE_THREADED").is_ok_and(|v| v != "0");
+
+    if single_threaded {
+        return 1;
+    }
+
+    if let Some(n) = env::var("ONEBRC_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+    {
+        return n.max(1);
+    }
+
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Which `SimdBuildHasher` to hash station names with. `ONEBRC_SEED` pins a
+/// fixed seed for reproducible runs (e.g. benchmarking); otherwise every
+/// process gets its own random seed.
+fn resolve_build_hasher() -> SimdBuildHasher {
+    match env::var("ONEBRC_SEED")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        Some(seed) => SimdBuildHasher::with_seed(seed),
+        None => SimdBuildHasher::new(),
+    }
+}
+
+/// Aggregate `map` using `threads` workers, each owning an independent,
+/// newline-aligned sub-range of the input.
+fn aggregate(
+    map: &[u8],
+    threads: usize,
+    hasher: SimdBuildHasher,
+) -> HashMap<Vec<u8>, Stats, SimdBuildHasher> {
+    let mut stats = HashMap::with_hasher(hasher);
+
+    if threads <= 1 {
+        merge_into(&mut stats, process_chunk(map, hasher));
+        return stats;
+    }
+
+    let partials = thread::scope(|scope| {
+        let handles: Vec<_> = chunk_bounds(map, threads)
+            .into_iter()
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| scope.spawn(move || process_chunk(&map[start..end], hasher)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    for partial in partials {
+        merge_into(&mut stats, partial);
+    }
+    stats
+}
+
+/// Aggregate from any `Read` source (stdin, a pipe, a non-seekable file)
+/// that `mmap` can't handle. Reads fixed-size buffers, carrying the
+/// trailing partial line of one buffer over to prefix the next.
+fn aggregate_stream<R: Read>(
+    mut reader: R,
+    hasher: SimdBuildHasher,
+) -> HashMap<Vec<u8>, Stats, SimdBuildHasher> {
+    const BUF_SIZE: usize = 8 * 1024 * 1024;
+
+    let mut stats = HashMap::with_hasher(hasher);
+    let mut buf = vec![0u8; BUF_SIZE];
+    let mut carry: Vec<u8> = Vec::new();
+
+    loop {
+        let filled = fill_buffer(&mut reader, &mut buf);
+        if filled == 0 {
+            break;
+        }
+
+        let data = &buf[..filled];
+        let split = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        if split == 0 {
+            carry.extend_from_slice(data);
+            continue;
+        }
+
+        carry.extend_from_slice(&data[..split]);
+        merge_into(&mut stats, process_chunk(&carry, hasher));
+        carry.clear();
+        carry.extend_from_slice(&data[split..]);
+    }
+
+    if !carry.is_empty() {
+        merge_into(&mut stats, process_chunk(&carry, hasher));
+    }
+
+    stats
+}
+
+/// Fill `buf` as full as possible from `reader`, looping over short reads.
+/// Returns the number of bytes filled; `0` means the source is exhausted.
+fn fill_buffer<R: Read>(reader: &mut R, buf: &mut [u8]) -> usize {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => panic!("{e}"),
+        }
+    }
+    filled
+}
+
+/// Split `map` into `n` contiguous byte ranges, snapping every interior
+/// boundary forward to the next newline so no line straddles two chunks.
+fn chunk_bounds(map: &[u8], n: usize) -> Vec<(usize, usize)> {
+    let mut bounds = Vec::with_capacity(n);
+    let mut start = 0;
+
+    for i in 1..n {
+        let target = map.len() * i / n;
+        let boundary = match find_next_newline(&map[target..]) {
+            Some(offset) => target + offset + 1,
+            None => map.len(),
+        };
+        bounds.push((start, boundary));
+        start = boundary;
+    }
+    bounds.push((start, map.len()));
+    bounds
+}
+
+/// Parse and aggregate one newline-delimited chunk in isolation, borrowing
+/// station names straight out of `chunk` and advancing by whole records.
+///
+/// `chunk_bounds` only snaps *interior* boundaries to a newline, so the
+/// final chunk of the whole input may end without a trailing `\n` (a file,
+/// or piped stream, with no final newline). That last, newline-less record
+/// is flushed explicitly once the main loop runs out of `;`+`\n` pairs.
+fn process_chunk(chunk: &[u8], hasher: SimdBuildHasher) -> HashMap<&[u8], Stats, SimdBuildHasher> {
+    let mut stats = HashMap::with_hasher(hasher);
+    let mut rest = chunk;
+
+    while let Some((semicolon, newline)) = find_semicolon_then_newline(rest) {
+        record(
+            &mut stats,
+            &rest[..semicolon],
+            &rest[semicolon + 1..newline],
+        );
+        rest = &rest[newline + 1..];
+    }
+
+    if let Some(semicolon) = find_next_semicolon(rest) {
+        record(&mut stats, &rest[..semicolon], &rest[semicolon + 1..]);
+    }
+
+    stats
+}
+
+fn record<'a>(
+    stats: &mut HashMap<&'a [u8], Stats, SimdBuildHasher>,
+    station: &'a [u8],
+    temp: &[u8],
+) {
+    let temperature = parse_temp(temp);
+    let entry = stats.entry(station).or_insert((i16::MAX, 0, 0, i16::MIN));
+    entry.0 = entry.0.min(temperature);
+    entry.1 += i64::from(temperature);
+    entry.2 += 1;
+    entry.3 = entry.3.max(temperature);
+}
+
+/// Fold one worker's partial stats into the final map, combining
+/// min/sum/count/max per station.
+fn merge_into(
+    dst: &mut HashMap<Vec<u8>, Stats, SimdBuildHasher>,
+    src: HashMap<&[u8], Stats, SimdBuildHasher>,
+) {
+    for (station, (min, sum, count, max)) in src {
+        let entry = dst
+            .entry(station.to_vec())
+            .or_insert((i16::MAX, 0, 0, i16::MIN));
+        entry.0 = entry.0.min(min);
+        entry.1 += sum;
+        entry.2 += count;
+        entry.3 = entry.3.max(max);
+    }
+}
+
 /**
  * parsing logic
  */
@@ -105,3 +320,59 @@ fn memmap(f: &File) -> &'_ [u8] {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_for(input: &[u8], threads: usize) -> BTreeMap<String, Stats> {
+        let stats = aggregate(input, threads, SimdBuildHasher::with_seed(0));
+        stats
+            .into_iter()
+            .map(|(k, v)| (unsafe { String::from_utf8_unchecked(k) }, v))
+            .collect()
+    }
+
+    #[test]
+    fn chunked_aggregation_matches_across_thread_counts() {
+        let input = b"Station A;10.0\nStation B;-3.5\nStation A;20.0\nStation C;0.0\nStation B;4.5\nStation A;5.5\n";
+
+        let expected = stats_for(input, 1);
+        assert_eq!(expected[&"Station A".to_string()], (55, 355, 3, 200));
+        assert_eq!(expected[&"Station B".to_string()], (-35, 10, 2, 45));
+        assert_eq!(expected[&"Station C".to_string()], (0, 0, 1, 0));
+
+        for threads in [2, 3, 4, 8] {
+            assert_eq!(stats_for(input, threads), expected, "threads = {threads}");
+        }
+    }
+
+    #[test]
+    fn process_chunk_flushes_final_record_without_trailing_newline() {
+        let input = b"Station A;10.0\nStation B;-3.5\nStation A;5.5";
+
+        let stats: BTreeMap<String, Stats> = process_chunk(input, SimdBuildHasher::with_seed(0))
+            .into_iter()
+            .map(|(k, v)| (unsafe { String::from_utf8_unchecked(k.to_vec()) }, v))
+            .collect();
+
+        assert_eq!(stats[&"Station A".to_string()], (55, 155, 2, 100));
+        assert_eq!(stats[&"Station B".to_string()], (-35, -35, 1, -35));
+    }
+
+    #[test]
+    fn chunk_bounds_partitions_without_gaps_or_overlap() {
+        let input = b"a;1.0\nb;2.0\nccc;3.0\nd;4.0\n";
+
+        for n in 1..=6 {
+            let bounds = chunk_bounds(input, n);
+            let mut cursor = 0;
+            for (start, end) in &bounds {
+                assert_eq!(*start, cursor);
+                assert!(*end <= input.len());
+                cursor = *end;
+            }
+            assert_eq!(cursor, input.len(), "n = {n}");
+        }
+    }
+}
@@ -1,61 +1,204 @@
-use std::{hash::BuildHasher, simd::Simd};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    simd::Simd,
+    sync::OnceLock,
+};
 
-#[derive(Default)]
+/// Number of 64-bit accumulator lanes, matching the width of the SIMD
+/// registers we mix with (8 lanes x 8 bytes = 64-byte stripes).
+const LANES: usize = 8;
+/// Bytes absorbed per vectorized mixing step.
+const STRIPE: usize = LANES * 8;
+
+const PRIME_1: u64 = 0x517cc1b727220a95;
+const PRIME_2: u64 = 0x2545f4914f6cdd1d;
+const PRIME_3: u64 = 0xff51afd7ed558ccd;
+
+/// Eight distinct odd primes used to seed the accumulator lanes, chosen so
+/// that each lane starts from an independent dependency chain.
+const LANE_SEEDS: [u64; LANES] = [
+    0x9e3779b97f4a7c55,
+    0xc2b2ae3d27d4eb4f,
+    0x165667b19e3779f9,
+    0x85ebca6b2b0e629f,
+    0x27d4eb2f16572eab,
+    0xff51afd7ed558ccd,
+    0xc4ceb9fe1a85eccd,
+    0x9e3779b185ebca87,
+];
+
+/// A small xxh3-style hasher: eight parallel 64-bit accumulator lanes mixed
+/// with `Simd<u64, 8>` ops, folded down and avalanched in `finish`.
+///
+/// The result only depends on the concatenated byte stream and its total
+/// length, never on how `write` happened to be chunked by the caller, so a
+/// sub-stripe remainder is buffered across calls and folded in at the end.
 pub(super) struct SimdHasher {
-    state: u64,
+    acc: Simd<u64, LANES>,
+    buf: [u8; STRIPE],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl SimdHasher {
+    pub(super) fn with_seed(seed: u64) -> Self {
+        let seeded: [u64; LANES] = core::array::from_fn(|i| {
+            LANE_SEEDS[i] ^ seed.wrapping_add(i as u64).wrapping_mul(PRIME_1)
+        });
+
+        Self {
+            acc: Simd::from_array(seeded),
+            buf: [0; STRIPE],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    #[inline]
+    fn absorb_stripe(&mut self, stripe: &[u8]) {
+        let lanes: [u64; LANES] = core::array::from_fn(|i| {
+            u64::from_ne_bytes(stripe[i * 8..i * 8 + 8].try_into().unwrap())
+        });
+
+        self.acc = (self.acc ^ Simd::from_array(lanes)) * Simd::splat(PRIME_1);
+    }
+}
+
+impl Default for SimdHasher {
+    fn default() -> Self {
+        Self::with_seed(0)
+    }
 }
 
 impl std::hash::Hasher for SimdHasher {
-    fn write(&mut self, bytes: &[u8]) {
-        // We use 16-byte chunks as baseline (128-bit SIMD) but detect AVX2 for 32-byte chunks
-        #[cfg(target_feature = "avx2")]
-        const CHUNK_SIZE: usize = 32; // 256-bit AVX2
-        #[cfg(not(target_feature = "avx2"))]
-        const CHUNK_SIZE: usize = 16; // 128-bit SSE
-
-        let chunks = bytes.chunks_exact(CHUNK_SIZE);
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+
+        if self.buf_len > 0 {
+            let need = STRIPE - self.buf_len;
+            let take = need.min(bytes.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&bytes[..take]);
+            self.buf_len += take;
+            bytes = &bytes[take..];
+
+            if self.buf_len < STRIPE {
+                return;
+            }
+
+            let stripe = self.buf;
+            self.absorb_stripe(&stripe);
+            self.buf_len = 0;
+        }
+
+        let chunks = bytes.chunks_exact(STRIPE);
         let remainder = chunks.remainder();
 
         for chunk in chunks {
-            // Load entire chunk into SIMD register at once
-            let simd_vec = Simd::<u8, CHUNK_SIZE>::from_slice(chunk);
-            let bytes: [u8; CHUNK_SIZE] = simd_vec.to_array();
-
-            // Process in 8-byte blocks - optimal for u64 operations
-            for i in (0..CHUNK_SIZE).step_by(8) {
-                let block = u64::from_ne_bytes(bytes[i..i + 8].try_into().unwrap());
-                // Mix block with hash state using XOR and prime multiplication
-                self.state = (block ^ self.state).wrapping_mul(0x517cc1b727220a95);
-            }
+            self.absorb_stripe(chunk);
+        }
+
+        self.buf[..remainder.len()].copy_from_slice(remainder);
+        self.buf_len = remainder.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let acc = self.acc.to_array();
+
+        // Merge the eight lanes into one value.
+        let mut result = self.total_len.wrapping_mul(PRIME_1);
+        for i in 0..4 {
+            result ^= (acc[2 * i] ^ acc[2 * i + 1]).wrapping_mul(PRIME_2);
         }
 
-        // Process remaining bytes in 8-byte blocks where possible
+        // Fold the buffered sub-stripe remainder in scalar form.
         let mut i = 0;
-        while i < remainder.len() {
-            if i + 8 <= remainder.len() {
-                let block = u64::from_ne_bytes(remainder[i..i + 8].try_into().unwrap());
-                self.state = (block ^ self.state).wrapping_mul(0x517cc1b727220a95);
+        while i < self.buf_len {
+            if i + 8 <= self.buf_len {
+                let block = u64::from_ne_bytes(self.buf[i..i + 8].try_into().unwrap());
+                result = (block ^ result).wrapping_mul(PRIME_1);
                 i += 8;
             } else {
-                // Final single-byte processing
-                self.state = (remainder[i] as u64 ^ self.state).wrapping_mul(0x517cc1b727220a95);
+                result = (self.buf[i] as u64 ^ result).wrapping_mul(PRIME_1);
                 i += 1;
             }
         }
+
+        // Final avalanche so low bits are well distributed too.
+        result ^= result >> 33;
+        result = result.wrapping_mul(PRIME_2);
+        result ^= result >> 29;
+        result = result.wrapping_mul(PRIME_3);
+        result ^= result >> 32;
+        result
     }
+}
 
-    fn finish(&self) -> u64 {
-        self.state
+/// A `BuildHasher` that perturbs every `SimdHasher` it creates with a
+/// per-process seed, so a crafted input can no longer target a fixed
+/// bucket layout. Hashers built from the same `SimdBuildHasher` stay
+/// deterministic relative to each other within a run.
+#[derive(Clone, Copy)]
+pub(super) struct SimdBuildHasher {
+    seed: u64,
+}
+
+impl SimdBuildHasher {
+    /// Derive a fresh seed from the OS-backed `RandomState` source, once per
+    /// process.
+    pub(super) fn new() -> Self {
+        static SEED: OnceLock<u64> = OnceLock::new();
+        let seed = *SEED.get_or_init(|| RandomState::new().build_hasher().finish());
+        Self { seed }
+    }
+
+    /// Build with a fixed seed, for reproducible runs (e.g. benchmarking).
+    pub(super) fn with_seed(seed: u64) -> Self {
+        Self { seed }
     }
 }
 
-#[derive(Default)]
-pub(super) struct SimdBuildHasher;
+impl Default for SimdBuildHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl BuildHasher for SimdBuildHasher {
     type Hasher = SimdHasher;
 
     fn build_hasher(&self) -> Self::Hasher {
-        SimdHasher::default()
+        SimdHasher::with_seed(self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_is_chunk_size_independent() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i % 251) as u8).collect();
+
+        let mut one_shot = SimdHasher::default();
+        one_shot.write(&data);
+
+        let mut piecewise = SimdHasher::default();
+        for chunk in data.chunks(7) {
+            piecewise.write(chunk);
+        }
+
+        assert_eq!(one_shot.finish(), piecewise.finish());
+    }
+
+    #[test]
+    fn with_seed_perturbs_hash_output() {
+        let mut a = SimdHasher::with_seed(1);
+        a.write(b"Station A;12.3");
+
+        let mut b = SimdHasher::with_seed(2);
+        b.write(b"Station A;12.3");
+
+        assert_ne!(a.finish(), b.finish());
     }
 }
@@ -1,24 +1,53 @@
-use libc::memchr;
+use std::simd::prelude::*;
 
+// 32-byte blocks when AVX2 is enabled at compile time, 16-byte blocks otherwise.
+#[cfg(target_feature = "avx2")]
+const LANES: usize = 32;
+#[cfg(not(target_feature = "avx2"))]
+const LANES: usize = 16;
+
+/// Find the next occurrence of `needle` in `haystack` using a portable-SIMD
+/// scan, with a scalar tail for the final sub-block.
 #[inline]
-/// Find the next occurrence of a byte in a slice using memchr.
 pub fn find_next_byte(haystack: &[u8], needle: u8) -> Option<usize> {
-    unsafe {
-        let ptr = memchr(haystack.as_ptr() as *const libc::c_void, needle as i32, haystack.len() as libc::size_t);
-        if ptr.is_null() {
-            None
-        } else {
-            Some((ptr as usize) - (haystack.as_ptr() as usize))
+    let needle_vec = Simd::<u8, LANES>::splat(needle);
+
+    let chunks = haystack.chunks_exact(LANES);
+    let remainder = chunks.remainder();
+    let mut base = 0;
+
+    for chunk in chunks {
+        let mask = Simd::<u8, LANES>::from_slice(chunk)
+            .simd_eq(needle_vec)
+            .to_bitmask();
+        if mask != 0 {
+            return Some(base + mask.trailing_zeros() as usize);
         }
+        base += LANES;
     }
+
+    remainder
+        .iter()
+        .position(|&b| b == needle)
+        .map(|i| base + i)
 }
 
-/// Find the next newline character using memchr.
+/// Find the next newline character.
 pub fn find_next_newline(haystack: &[u8]) -> Option<usize> {
     find_next_byte(haystack, b'\n')
 }
 
-/// Find the next semicolon character using memchr.
+/// Find the next semicolon character.
 pub fn find_next_semicolon(haystack: &[u8]) -> Option<usize> {
     find_next_byte(haystack, b';')
-}
\ No newline at end of file
+}
+
+/// Locate the `;` and the terminating `\n` for one record: the semicolon
+/// search covers the station name, then the newline search starts just past
+/// the `;`, so the station name is not scanned a second time. Bytes after
+/// the `;` that share its SIMD block are compared by both searches.
+pub fn find_semicolon_then_newline(haystack: &[u8]) -> Option<(usize, usize)> {
+    let semicolon = find_next_semicolon(haystack)?;
+    let newline = semicolon + 1 + find_next_newline(&haystack[semicolon + 1..])?;
+    Some((semicolon, newline))
+}